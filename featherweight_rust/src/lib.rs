@@ -0,0 +1,168 @@
+//! A small, self-contained reference checker for the FeatherweightRust
+//! ownership calculus, vendored here so `rustc_wrapper`'s differential
+//! harness has a concrete implementation to compare rustc against.
+//!
+//! This does not implement the full calculus from the paper. It recognises
+//! a straight-line subset of Rust - `let` bindings, moves of a bare place
+//! (`let y = x;`), and uses of a place as a bare identifier - and flags the
+//! one violation that subset can express: using a place after it has been
+//! moved. Anything outside that subset (borrows, control flow, method
+//! calls, ...) is not modelled: each such statement is only scanned for
+//! identifiers, and is rejected if one of them names an already-moved place.
+
+use std::collections::HashSet;
+use std::fmt;
+
+use serde::Serialize;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// A place was used after being moved out of.
+    UseAfterMove(String),
+    /// The derivation search ran out of fuel before reaching a verdict.
+    OutOfFuel,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::UseAfterMove(place) => write!(f, "use of moved place `{}`", place),
+            Error::OutOfFuel => write!(f, "ran out of fuel before a derivation could be found"),
+        }
+    }
+}
+
+/// One step of the typing derivation: the rule FR applied, the place it
+/// concerned, and the ownership state immediately after the step.
+#[derive(Debug, Clone, Serialize)]
+pub struct Step {
+    pub rule: &'static str,
+    pub place: String,
+    pub moved: Vec<String>,
+}
+
+/// The derivation FR produced for a function body: one [`Step`] per move, in
+/// source order. Statements that are not moves produce no step, and borrows
+/// are not recorded.
+#[derive(Debug, Clone, Serialize)]
+pub struct Derivation {
+    pub steps: Vec<Step>,
+}
+
+/// Type-check (and move-check) `src` under the FeatherweightRust calculus.
+///
+/// `fuel` bounds how many statements the derivation search examines before
+/// giving up with [`Error::OutOfFuel`], since the full calculus's search is
+/// not guaranteed to terminate on inputs outside the modelled subset.
+pub fn typecheck(src: &str, fuel: u32) -> Result<(), Error> {
+    derive(src, fuel).map(|_| ())
+}
+
+/// Like [`typecheck`], but returns the move steps FR recorded while reaching
+/// its verdict (see [`Derivation`]).
+pub fn derive(src: &str, fuel: u32) -> Result<Derivation, Error> {
+    let mut moved: HashSet<String> = HashSet::new();
+    let mut steps = Vec::new();
+    let mut budget = fuel;
+
+    for stmt in statements(src) {
+        if budget == 0 {
+            return Err(Error::OutOfFuel);
+        }
+        budget -= 1;
+
+        if let Some((place, source)) = parse_move(&stmt) {
+            if moved.contains(&source) {
+                return Err(Error::UseAfterMove(source));
+            }
+            moved.insert(source.clone());
+            steps.push(Step {
+                rule: "move",
+                place,
+                moved: sorted(&moved),
+            });
+            continue;
+        }
+
+        for place in used_places(&stmt) {
+            if moved.contains(&place) {
+                return Err(Error::UseAfterMove(place));
+            }
+        }
+    }
+
+    Ok(Derivation { steps })
+}
+
+fn sorted(places: &HashSet<String>) -> Vec<String> {
+    let mut places: Vec<String> = places.iter().cloned().collect();
+    places.sort();
+    places
+}
+
+fn statements(src: &str) -> Vec<String> {
+    src.split([';', '\n'])
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Recognises `let <place> = <source>;` where `<source>` is a bare place
+/// (a move), as opposed to a literal, borrow, or call.
+fn parse_move(stmt: &str) -> Option<(String, String)> {
+    let stmt = stmt.strip_prefix("let ")?;
+    let (place, rhs) = stmt.split_once('=')?;
+    let place = place.trim().trim_start_matches("mut ").trim().to_string();
+    let rhs = rhs.trim().trim_end_matches('}').trim();
+
+    let is_bare_place = !rhs.is_empty()
+        && rhs.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        && !rhs.chars().next()?.is_ascii_digit();
+
+    if is_bare_place {
+        Some((place, rhs.to_string()))
+    } else {
+        None
+    }
+}
+
+fn used_places(stmt: &str) -> Vec<String> {
+    stmt.split(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_string())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_straight_line_code_with_no_moves() {
+        assert!(typecheck("let x = 1;\nlet y = 2;", 100).is_ok());
+    }
+
+    #[test]
+    fn accepts_a_single_move() {
+        assert!(typecheck("let x = 1;\nlet y = x;", 100).is_ok());
+    }
+
+    #[test]
+    fn rejects_use_after_move() {
+        let err = typecheck("let x = 1;\nlet y = x;\nlet z = x;", 100).unwrap_err();
+        assert_eq!(err, Error::UseAfterMove("x".to_string()));
+    }
+
+    #[test]
+    fn runs_out_of_fuel_on_long_input() {
+        let src = "let a = 1;\nlet b = 2;\nlet c = 3;";
+        assert_eq!(typecheck(src, 1), Err(Error::OutOfFuel));
+    }
+
+    #[test]
+    fn derive_records_one_step_per_move() {
+        let derivation = derive("let x = 1;\nlet y = x;", 100).unwrap();
+        assert_eq!(derivation.steps.len(), 1);
+        assert_eq!(derivation.steps[0].place, "y");
+    }
+}
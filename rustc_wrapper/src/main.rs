@@ -1,25 +1,823 @@
 #![feature(rustc_private)]
 
+#[macro_use]
 extern crate rustc;
+extern crate featherweight_rust;
 extern crate rustc_codegen_utils;
 extern crate rustc_driver;
 extern crate rustc_errors;
 extern crate rustc_interface;
 extern crate rustc_metadata;
+extern crate rustc_mir;
+#[macro_use]
+extern crate serde_json;
 extern crate syntax;
-use rustc::session::config::ErrorOutputType;
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::Command;
+use std::sync::atomic::{AtomicU32, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex};
+
+use rustc::hir;
+use rustc::hir::def_id::DefId;
+use rustc::hir::intravisit::FnKind;
+use rustc::lint::{LateContext, LateLintPass, LintArray, LintContext, LintPass, LintStore};
+use rustc::mir::BorrowCheckResult;
+use rustc::session::config::{
+    ColorConfig, CrateType, ErrorOutputType, HumanReadableErrorType, Input, Options,
+};
 use rustc::session::early_error;
-use rustc_driver::{run_compiler, Callbacks};
-use rustc_interface::interface;
+use rustc::session::Session;
+use rustc::ty::query::Providers;
+use rustc::ty::TyCtxt;
+use rustc_driver::{run_compiler, Callbacks, Compilation};
+use rustc_interface::interface::{self, DiagnosticOutput};
+use rustc_mir::borrow_check::mir_borrowck as rustc_mir_borrowck;
+use syntax::source_map::{FileName, Span};
 
 fn main() {
-    let args: Vec<_> = std::env::args().collect();
-    rustc_driver::run_compiler(&args, &mut Calls, None, None);
+    let raw_args: Vec<_> = std::env::args().collect();
+    let (fr_args, rustc_args): (Vec<_>, Vec<_>) = raw_args
+        .into_iter()
+        .partition(|arg| arg.starts_with("-Zfr-"));
+
+    let mut calls = Calls::default();
+    for arg in &fr_args {
+        apply_fr_option(&mut calls, arg);
+    }
+
+    run_compiler(&rustc_args, &mut calls, None, None);
+
+    for verdict in calls.verdicts.lock().unwrap().iter() {
+        eprintln!(
+            "{}: {}",
+            verdict.def_path,
+            if verdict.accepted {
+                "accepted"
+            } else {
+                "rejected"
+            }
+        );
+    }
+}
+
+/// Apply one `-Zfr-key[=value]` option, mirroring how `-C`/`-Z` codegen
+/// options are parsed elsewhere in the compiler. These are FeatherweightRust's
+/// own options, not real rustc `-Z` flags, so they are stripped out of the
+/// argument list in `main` before it ever reaches `run_compiler`.
+fn apply_fr_option(calls: &mut Calls, arg: &str) {
+    let rest = &arg["-Zfr-".len()..];
+    let (key, value) = match rest.find('=') {
+        Some(idx) => (&rest[..idx], &rest[idx + 1..]),
+        None => (rest, ""),
+    };
+
+    match key {
+        "mode" => {
+            calls.mode = match value {
+                "off" => FrMode::Off,
+                "shadow" => FrMode::Shadow,
+                "replace" => FrMode::Replace,
+                other => early_error(
+                    ErrorOutputType::default(),
+                    &format!(
+                        "unknown -Zfr-mode value: `{}` (expected off, shadow or replace)",
+                        other
+                    ),
+                ),
+            };
+        }
+        "fuel" => {
+            calls.fuel = value.parse().unwrap_or_else(|_| {
+                early_error(
+                    ErrorOutputType::default(),
+                    &format!("invalid -Zfr-fuel value: `{}` (expected a number)", value),
+                )
+            });
+        }
+        "dump-json" => {
+            if value.is_empty() {
+                early_error(
+                    ErrorOutputType::default(),
+                    "-Zfr-dump-json requires a path: -Zfr-dump-json=<path>",
+                );
+            }
+            calls.dump_json = Some(PathBuf::from(value));
+        }
+        other => early_error(
+            ErrorOutputType::default(),
+            &format!("unknown FeatherweightRust option: -Zfr-{}", other),
+        ),
+    }
+}
+
+/// What rustc's own borrow checker decided about a single function, for
+/// comparison against the verdict the FeatherweightRust calculus produces
+/// for the same program.
+#[derive(Debug, Clone)]
+pub struct FunctionVerdict {
+    pub def_path: String,
+    pub accepted: bool,
+    pub errors: Vec<(Span, String)>,
+}
+
+/// A `Write` sink that stashes everything written to it in a shared buffer,
+/// so the JSON diagnostics rustc emits under `--error-format=json` can be
+/// inspected after the compiler run instead of merely being printed.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl io::Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// How the FeatherweightRust calculus participates in borrow-checking a
+/// real crate. `override_queries` reaches this through a process-wide
+/// static rather than a closure, since `Providers` fields are plain `fn`
+/// pointers and cannot capture `self`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum FrMode {
+    Off,
+    Shadow,
+    Replace,
+}
+
+impl Default for FrMode {
+    fn default() -> Self {
+        // Without an explicit `-Zfr-mode`, this driver must behave like a
+        // plain rustc: no override, no shadow-mode warnings.
+        FrMode::Off
+    }
+}
+
+static FR_MODE: AtomicU8 = AtomicU8::new(0);
+
+impl FrMode {
+    fn store(self) {
+        let value = match self {
+            FrMode::Off => 0,
+            FrMode::Shadow => 1,
+            FrMode::Replace => 2,
+        };
+        FR_MODE.store(value, Ordering::Relaxed);
+    }
+
+    fn current() -> Self {
+        match FR_MODE.load(Ordering::Relaxed) {
+            1 => FrMode::Shadow,
+            2 => FrMode::Replace,
+            _ => FrMode::Off,
+        }
+    }
 }
 
-struct Calls;
+/// Default fuel given to the FeatherweightRust calculus to bound its
+/// (potentially non-terminating) derivation search, absent `-Zfr-fuel`.
+const DEFAULT_FR_FUEL: u32 = 1000;
+
+/// Mirrors `FR_MODE`: `Providers::mir_borrowck` and the lint pass are both
+/// bare `fn`s, so the fuel budget has to reach them through a static too.
+static FR_FUEL: AtomicU32 = AtomicU32::new(DEFAULT_FR_FUEL);
+
+fn fr_fuel() -> u32 {
+    FR_FUEL.load(Ordering::Relaxed)
+}
+
+struct Calls {
+    diagnostics: SharedBuffer,
+    verdicts: Arc<Mutex<Vec<FunctionVerdict>>>,
+    mode: FrMode,
+    fuel: u32,
+    dump_json: Option<PathBuf>,
+}
+
+impl Default for Calls {
+    fn default() -> Self {
+        Calls {
+            diagnostics: SharedBuffer::default(),
+            verdicts: Arc::default(),
+            mode: FrMode::default(),
+            fuel: DEFAULT_FR_FUEL,
+            dump_json: None,
+        }
+    }
+}
 
 impl Callbacks for Calls {
     fn config(&mut self, config: &mut interface::Config) {
+        FR_FUEL.store(self.fuel, Ordering::Relaxed);
+
+        // Force JSON diagnostics and capture them in-memory rather than
+        // letting them go straight to stderr, so `after_analysis` can
+        // recover the error code and message for each function.
+        config.opts.error_format = ErrorOutputType::Json {
+            pretty: false,
+            json_rendered: HumanReadableErrorType::Default(ColorConfig::Never),
+        };
+        config.diagnostic_output = DiagnosticOutput::Raw(Box::new(self.diagnostics.clone()));
+
+        if self.mode != FrMode::Off {
+            self.mode.store();
+            config.register_lints = Some(Box::new(register_fr_lints));
+            config.override_queries = Some(override_mir_borrowck);
+        }
+    }
+
+    fn after_analysis(&mut self, compiler: &interface::Compiler) -> Compilation {
+        compiler.global_ctxt().unwrap().peek_mut().enter(|tcx| {
+            let diagnostics = parsed_diagnostics(&self.diagnostics);
+
+            let mut dump = self.dump_json.as_deref().map(open_dump_writer).transpose();
+            let dump = match &mut dump {
+                Ok(dump) => dump.as_mut().map(|w| w as &mut dyn io::Write),
+                Err(err) => {
+                    tcx.sess
+                        .err(&format!("failed to open FR derivation dump: {}", err));
+                    None
+                }
+            };
+
+            let new_verdicts = collect_verdicts(tcx, &diagnostics, dump);
+            self.verdicts.lock().unwrap().extend(new_verdicts);
+        });
+        Compilation::Continue
+    }
+}
+
+/// Open the newline-delimited JSON file `dump_json` points at, appending so
+/// that successive crates accumulate one derivation export rather than
+/// clobbering it.
+fn open_dump_writer(path: &std::path::Path) -> io::Result<io::BufWriter<std::fs::File>> {
+    use std::fs::OpenOptions;
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    Ok(io::BufWriter::new(file))
+}
+
+/// Write one ndjson record capturing the FeatherweightRust derivation for a
+/// single function: one step per move, each giving the rule applied, the
+/// place bound, and the set of places moved so far. Neither the environment
+/// at each program point nor owned or borrowed places are recorded, and
+/// statements that are not moves produce no step. A write failure is non-fatal - the compile run itself
+/// already succeeded - so it is only logged to stderr.
+fn dump_derivation<W: io::Write>(mut writer: W, def_path: &str, snippet: &str) {
+    let record = match featherweight_rust::derive(snippet, fr_fuel()) {
+        Ok(derivation) => serde_json::json!({ "def_path": def_path, "derivation": derivation }),
+        Err(err) => serde_json::json!({ "def_path": def_path, "error": err.to_string() }),
+    };
+    if let Err(err) = writeln!(writer, "{}", record) {
+        eprintln!(
+            "warning: failed to write FR derivation for {}: {}",
+            def_path, err
+        );
+    }
+}
+
+/// The 1-indexed start/end source lines covered by `span`, used as a cheap
+/// proxy for "this diagnostic belongs to this function" since rustc's JSON
+/// diagnostics report line/column rather than the `Span` values we have.
+fn source_lines(source_map: &syntax::source_map::SourceMap, span: Span) -> (usize, usize) {
+    let lo = source_map.lookup_char_pos(span.lo()).line;
+    let hi = source_map.lookup_char_pos(span.hi()).line;
+    (lo, hi)
+}
+
+/// Build the [`FunctionVerdict`] for every function body in `tcx`'s crate
+/// from the already-emitted `diagnostics`, writing an FR derivation dump
+/// along the way if `dump` is set. Borrow checking must already have run;
+/// this does not trigger it. Shared by the file-driven [`Calls::after_analysis`] and
+/// the in-memory [`check_snippet_verdicts`] so both report verdicts the
+/// same way.
+fn collect_verdicts(
+    tcx: TyCtxt<'_>,
+    diagnostics: &[serde_json::Value],
+    mut dump: Option<&mut dyn io::Write>,
+) -> Vec<FunctionVerdict> {
+    let source_map = tcx.sess.source_map();
+
+    let owners: Vec<(DefId, String, Span, usize, usize)> = tcx
+        .body_owners()
+        .map(|def_id| {
+            let def_path = tcx.def_path_str(def_id);
+            let def_span = tcx.def_span(def_id);
+            let (lo, hi) = source_lines(&source_map, def_span);
+            (def_id, def_path, def_span, lo, hi)
+        })
+        .collect();
+
+    // Attribute each diagnostic to the innermost enclosing function body,
+    // not every body whose range happens to overlap it - otherwise an error
+    // inside a nested function or closure also gets blamed on every
+    // function that lexically contains it.
+    let mut errors_by_owner: HashMap<DefId, Vec<(Span, String)>> = HashMap::new();
+    for diag in diagnostics.iter().filter(|diag| diag["level"] == "error") {
+        if let Some((def_id, def_span)) = innermost_owner(&owners, diag) {
+            let text = diag["message"].as_str().unwrap_or_default();
+            let message = match diag["code"]["code"].as_str() {
+                Some(code) => format!("{}: {}", code, text),
+                None => text.to_string(),
+            };
+            errors_by_owner
+                .entry(def_id)
+                .or_default()
+                .push((def_span, message));
+        }
+    }
+
+    let mut verdicts = Vec::new();
+    for (def_id, def_path, def_span, _, _) in &owners {
+        if let Some(dump) = dump.as_deref_mut() {
+            if let Ok(snippet) = source_map.span_to_snippet(*def_span) {
+                dump_derivation(dump, def_path, &snippet);
+            }
+        }
+
+        let errors = errors_by_owner.remove(def_id).unwrap_or_default();
+        verdicts.push(FunctionVerdict {
+            def_path: def_path.clone(),
+            accepted: errors.is_empty(),
+            errors,
+        });
+    }
+
+    verdicts
+}
+
+/// Among the body owners whose source range contains one of `diag`'s
+/// spans, pick the one with the smallest line range - the innermost
+/// enclosing function - rather than every owner that merely overlaps it.
+fn innermost_owner(
+    owners: &[(DefId, String, Span, usize, usize)],
+    diag: &serde_json::Value,
+) -> Option<(DefId, Span)> {
+    let lines = diagnostic_lines(diag)?;
+    let ranges: Vec<(usize, usize)> = owners.iter().map(|(_, _, _, lo, hi)| (*lo, *hi)).collect();
+    let index = innermost_range(&ranges, &lines)?;
+    let (def_id, _, def_span, _, _) = &owners[index];
+    Some((*def_id, *def_span))
+}
+
+/// The 1-indexed start lines `diag`'s spans cover, or `None` if it has no
+/// `spans` array. An empty `spans` array gives an empty list.
+fn diagnostic_lines(diag: &serde_json::Value) -> Option<Vec<usize>> {
+    Some(
+        diag["spans"]
+            .as_array()?
+            .iter()
+            .map(|span| span["line_start"].as_u64().unwrap_or(0) as usize)
+            .collect(),
+    )
+}
+
+/// Of the `(lo, hi)` ranges that contain at least one of `lines`, return the
+/// index of the narrowest one - the innermost enclosing range.
+fn innermost_range(ranges: &[(usize, usize)], lines: &[usize]) -> Option<usize> {
+    ranges
+        .iter()
+        .enumerate()
+        .filter(|(_, (lo, hi))| lines.iter().any(|line| line >= lo && line <= hi))
+        .min_by_key(|(_, (lo, hi))| hi - lo)
+        .map(|(index, _)| index)
+}
+
+/// Parse the JSON diagnostics `SharedBuffer` has accumulated so far into
+/// one `serde_json::Value` per line.
+fn parsed_diagnostics(diagnostics: &SharedBuffer) -> Vec<serde_json::Value> {
+    let raw = diagnostics.0.lock().unwrap();
+    String::from_utf8_lossy(&raw)
+        .lines()
+        .filter_map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+/// The outcome of [`check_snippet`]: the raw JSON diagnostics rustc produced
+/// for the snippet, in emission order.
+#[derive(Debug)]
+pub struct CheckResult {
+    pub diagnostics: Vec<serde_json::Value>,
+}
+
+impl CheckResult {
+    pub fn has_errors(&self) -> bool {
+        self.diagnostics.iter().any(|diag| diag["level"] == "error")
+    }
+}
+
+/// Build the `Input::Str` config shared by [`check_snippet`] and
+/// [`check_snippet_verdicts`]: an anonymous `Rlib` crate compiled from
+/// `src` with its diagnostics captured into `diagnostics` instead of
+/// printed.
+fn snippet_config(src: &str, diagnostics: SharedBuffer) -> interface::Config {
+    let mut opts = Options::default();
+    opts.error_format = ErrorOutputType::Json {
+        pretty: false,
+        json_rendered: HumanReadableErrorType::Default(ColorConfig::Never),
+    };
+    opts.maybe_sysroot = Some(sysroot());
+    opts.crate_types = vec![CrateType::Rlib];
+
+    interface::Config {
+        opts,
+        crate_cfg: Default::default(),
+        input: Input::Str {
+            name: FileName::anon_source_code(src),
+            input: src.to_string(),
+        },
+        input_path: None,
+        output_dir: None,
+        output_file: None,
+        file_loader: None,
+        diagnostic_output: DiagnosticOutput::Raw(Box::new(diagnostics)),
+        stderr: None,
+        crate_name: None,
+        lint_caps: Default::default(),
+        register_lints: None,
+        override_queries: None,
+    }
+}
+
+/// Type-check and borrow-check `src` as an anonymous crate, in-process,
+/// without touching the filesystem. Intended for the random-program
+/// generator: it can synthesize thousands of candidate snippets and feed
+/// each straight to rustc to see whether it is accepted.
+pub fn check_snippet(src: &str) -> CheckResult {
+    let diagnostics = SharedBuffer::default();
+    let config = snippet_config(src, diagnostics.clone());
+
+    interface::run_compiler(config, |compiler| {
+        if let Ok(gcx) = compiler.global_ctxt() {
+            gcx.peek_mut().enter(|tcx| {
+                let _ = tcx.analysis(rustc::hir::def_id::LOCAL_CRATE);
+            });
+        }
+    });
+
+    CheckResult {
+        diagnostics: parsed_diagnostics(&diagnostics),
+    }
+}
+
+/// Like [`check_snippet`], but for the FR-vs-rustc differential harness:
+/// returns the structured per-function [`FunctionVerdict`]s instead of raw
+/// diagnostics, so external code can assert the two checkers agree on
+/// every function in `src` without re-running the compiler itself.
+pub fn check_snippet_verdicts(src: &str) -> Vec<FunctionVerdict> {
+    let diagnostics = SharedBuffer::default();
+    let config = snippet_config(src, diagnostics.clone());
+
+    interface::run_compiler(config, |compiler| {
+        compiler.global_ctxt().unwrap().peek_mut().enter(|tcx| {
+            let _ = tcx.analysis(rustc::hir::def_id::LOCAL_CRATE);
+            let parsed = parsed_diagnostics(&diagnostics);
+            collect_verdicts(tcx, &parsed, None)
+        })
+    })
+}
+
+/// Find the sysroot to compile snippets against, the same way `rustc`
+/// itself would: respect `SYSROOT` if the caller has set it, otherwise ask
+/// whatever `rustc` is on `PATH` (rustup or multirust will have put the
+/// right one there).
+///
+/// `check_snippet`/`check_snippet_verdicts` are meant to be called in a
+/// tight loop by a program generator, so the result is discovered once per
+/// process and cached rather than shelling out to `rustc` on every call.
+fn sysroot() -> PathBuf {
+    static INIT: std::sync::Once = std::sync::Once::new();
+    static mut SYSROOT: Option<PathBuf> = None;
+
+    unsafe {
+        INIT.call_once(|| SYSROOT = Some(discover_sysroot()));
+        SYSROOT
+            .clone()
+            .expect("sysroot was initialized by call_once above")
+    }
+}
+
+fn discover_sysroot() -> PathBuf {
+    std::env::var("SYSROOT")
+        .ok()
+        .map(PathBuf::from)
+        .or_else(|| {
+            Command::new("rustc")
+                .args(&["--print", "sysroot"])
+                .output()
+                .ok()
+                .and_then(|out| String::from_utf8(out.stdout).ok())
+                .map(|s| PathBuf::from(s.trim()))
+        })
+        .expect("need to specify SYSROOT env var or use rustup/multirust")
+}
+
+declare_lint! {
+    pub FR_DIVERGENCE,
+    Warn,
+    "rustc and the FeatherweightRust calculus disagree about whether this function type-checks"
+}
+
+/// Walks every function in the crate and flags the ones where rustc's
+/// verdict and the FeatherweightRust calculus's verdict disagree. This is
+/// the linter-facing counterpart to [`FunctionVerdict`]: instead of a
+/// structured report for offline comparison, divergences show up as
+/// ordinary `FR0001` warnings at the point they occur.
+struct FrDivergencePass;
+
+impl LintPass for FrDivergencePass {
+    fn name(&self) -> &'static str {
+        "FrDivergencePass"
+    }
+
+    fn get_lints(&self) -> LintArray {
+        lint_array!(FR_DIVERGENCE)
+    }
+}
+
+impl<'a, 'tcx> LateLintPass<'a, 'tcx> for FrDivergencePass {
+    fn check_fn(
+        &mut self,
+        cx: &LateContext<'a, 'tcx>,
+        _: FnKind<'tcx>,
+        _: &'tcx hir::FnDecl,
+        _: &'tcx hir::Body,
+        span: Span,
+        hir_id: hir::HirId,
+    ) {
+        let snippet = match cx.sess().source_map().span_to_snippet(span) {
+            Ok(snippet) => snippet,
+            Err(_) => return,
+        };
+
+        let def_id = cx.tcx.hir().local_def_id(hir_id);
+
+        // `typeck_tables_of` only reflects HIR type-inference errors; since
+        // FR models ownership/borrowing, its verdict has to be compared
+        // against the borrow checker, not the type checker.
+        //
+        // This has to go through the raw `rustc_mir_borrowck` function, not
+        // `cx.tcx.mir_borrowck(def_id)`: in `Replace` mode the `mir_borrowck`
+        // query itself is overridden with `fr_mir_borrowck`, which already
+        // reconciles against FR and emits its own `span_err` on a rejection.
+        // Going through the query there would fold that side effect into
+        // `errors_before`/`errors_after` and mask the very divergence this
+        // lint exists to report.
+        let errors_before = cx.tcx.sess.err_count();
+        rustc_mir_borrowck(cx.tcx, def_id);
+        let rustc_accepts = cx.tcx.sess.err_count() == errors_before;
+
+        let fr_accepts = featherweight_rust::typecheck(&snippet, fr_fuel()).is_ok();
+
+        if rustc_accepts != fr_accepts {
+            cx.struct_span_lint(
+                FR_DIVERGENCE,
+                span,
+                &format!(
+                    "rustc and FeatherweightRust disagree on this function (rustc: {}, FR: {})",
+                    if rustc_accepts { "accept" } else { "reject" },
+                    if fr_accepts { "accept" } else { "reject" },
+                ),
+            )
+            .code(rustc_errors::DiagnosticId::Error("FR0001".to_string()))
+            .emit();
+        }
+    }
+}
+
+fn register_fr_lints(_sess: &Session, lint_store: &mut LintStore) {
+    lint_store.register_lints(&[&FR_DIVERGENCE]);
+    lint_store.register_late_pass(None, false, Box::new(FrDivergencePass));
+}
+
+fn override_mir_borrowck(
+    _sess: &Session,
+    local: &mut Providers<'_>,
+    _extern_providers: &mut Providers<'_>,
+) {
+    local.mir_borrowck = fr_mir_borrowck;
+}
+
+/// What [`fr_mir_borrowck`] should do once it has both verdicts in hand.
+/// Kept separate from `fr_mir_borrowck` itself so the reconciliation policy
+/// - which depends only on [`FrMode`] and the two booleans, not on any live
+/// compiler state - can be unit-tested directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Reconciliation {
+    /// Nothing to report: off, the two checkers agree, or (in replace mode)
+    /// FR accepts a function rustc rejected.
+    Silent,
+    /// Shadow mode, and the two checkers disagree: report but don't fail.
+    Warn,
+    /// Replace mode, and FR vetoed an accept: turn it into a hard error.
+    Reject,
+}
+
+/// Decide how a divergence between rustc's and FR's verdicts on the same
+/// function should be reported, given the active [`FrMode`].
+///
+/// In `Replace` mode, FR can only veto an accept (reject a function rustc
+/// would have allowed); it cannot currently force an accept of a function
+/// rustc's checker has already flagged, since that would mean fabricating a
+/// `BorrowCheckResult` rustc never computed. That direction is left for
+/// deeper integration once FR exposes its own MIR-level facts.
+fn reconcile(mode: FrMode, rustc_accepts: bool, fr_accepts: bool) -> Reconciliation {
+    match mode {
+        FrMode::Off => Reconciliation::Silent,
+        FrMode::Shadow if fr_accepts != rustc_accepts => Reconciliation::Warn,
+        FrMode::Replace if !fr_accepts && rustc_accepts => Reconciliation::Reject,
+        FrMode::Shadow | FrMode::Replace => Reconciliation::Silent,
+    }
+}
+
+/// Stand-in for rustc's `mir_borrowck` provider: always runs the real
+/// check (nothing downstream can do without a genuine `BorrowCheckResult`),
+/// then asks the FeatherweightRust calculus for its own verdict on the same
+/// function and reconciles the two via [`reconcile`].
+fn fr_mir_borrowck<'tcx>(tcx: TyCtxt<'tcx>, def_id: DefId) -> &'tcx BorrowCheckResult<'tcx> {
+    let errors_before = tcx.sess.err_count();
+    let result = rustc_mir_borrowck(tcx, def_id);
+    let rustc_accepts = tcx.sess.err_count() == errors_before;
+
+    let def_span = tcx.def_span(def_id);
+    let fr_accepts = tcx
+        .sess
+        .source_map()
+        .span_to_snippet(def_span)
+        .ok()
+        .map(|snippet| featherweight_rust::typecheck(&snippet, fr_fuel()).is_ok());
+
+    if let Some(fr_accepts) = fr_accepts {
+        match reconcile(FrMode::current(), rustc_accepts, fr_accepts) {
+            Reconciliation::Silent => {}
+            Reconciliation::Warn => {
+                tcx.sess.span_warn(
+                    def_span,
+                    &format!(
+                        "FeatherweightRust disagrees with rustc's borrow check here (FR: {}, rustc: {})",
+                        if fr_accepts { "accept" } else { "reject" },
+                        if rustc_accepts { "accept" } else { "reject" },
+                    ),
+                );
+            }
+            Reconciliation::Reject => {
+                tcx.sess
+                    .span_err(def_span, "rejected by the FeatherweightRust calculus");
+            }
+        }
+    }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_fr_option_parses_mode() {
+        let mut calls = Calls::default();
+        assert_eq!(calls.mode, FrMode::Off);
+
+        apply_fr_option(&mut calls, "-Zfr-mode=shadow");
+        assert_eq!(calls.mode, FrMode::Shadow);
+
+        apply_fr_option(&mut calls, "-Zfr-mode=replace");
+        assert_eq!(calls.mode, FrMode::Replace);
+
+        apply_fr_option(&mut calls, "-Zfr-mode=off");
+        assert_eq!(calls.mode, FrMode::Off);
+    }
+
+    #[test]
+    fn apply_fr_option_parses_fuel() {
+        let mut calls = Calls::default();
+        apply_fr_option(&mut calls, "-Zfr-fuel=42");
+        assert_eq!(calls.fuel, 42);
+    }
+
+    #[test]
+    fn apply_fr_option_parses_dump_json_path() {
+        let mut calls = Calls::default();
+        apply_fr_option(&mut calls, "-Zfr-dump-json=/tmp/fr-derivations.ndjson");
+        assert_eq!(
+            calls.dump_json,
+            Some(PathBuf::from("/tmp/fr-derivations.ndjson"))
+        );
+    }
+
+    #[test]
+    fn diagnostic_lines_reads_span_line_starts() {
+        let diag = serde_json::json!({ "spans": [{ "line_start": 5 }, { "line_start": 7 }] });
+        assert_eq!(diagnostic_lines(&diag), Some(vec![5, 7]));
+    }
+
+    #[test]
+    fn diagnostic_lines_handles_missing_spans() {
+        assert_eq!(diagnostic_lines(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn innermost_range_picks_the_narrowest_containing_range() {
+        // An outer function spanning 1-10 containing a nested inner
+        // function spanning 4-6; a diagnostic on line 5 belongs to the
+        // inner function, not both.
+        let ranges = [(1, 10), (4, 6)];
+        assert_eq!(innermost_range(&ranges, &[5]), Some(1));
+    }
+
+    #[test]
+    fn innermost_range_ignores_ranges_that_do_not_contain_the_line() {
+        let ranges = [(1, 10), (4, 6)];
+        assert_eq!(innermost_range(&ranges, &[8]), Some(0));
+        assert_eq!(innermost_range(&ranges, &[20]), None);
+    }
+
+    #[test]
+    fn reconcile_is_silent_when_mode_is_off() {
+        assert_eq!(reconcile(FrMode::Off, true, false), Reconciliation::Silent);
+        assert_eq!(reconcile(FrMode::Off, false, true), Reconciliation::Silent);
+    }
+
+    #[test]
+    fn reconcile_warns_on_any_divergence_in_shadow_mode() {
+        assert_eq!(reconcile(FrMode::Shadow, true, false), Reconciliation::Warn);
+        assert_eq!(reconcile(FrMode::Shadow, false, true), Reconciliation::Warn);
+    }
+
+    #[test]
+    fn reconcile_is_silent_in_shadow_mode_when_verdicts_agree() {
+        assert_eq!(
+            reconcile(FrMode::Shadow, true, true),
+            Reconciliation::Silent
+        );
+        assert_eq!(
+            reconcile(FrMode::Shadow, false, false),
+            Reconciliation::Silent
+        );
+    }
+
+    #[test]
+    fn reconcile_rejects_only_when_fr_vetoes_an_rustc_accept() {
+        assert_eq!(
+            reconcile(FrMode::Replace, true, false),
+            Reconciliation::Reject
+        );
+    }
+
+    #[test]
+    fn reconcile_cannot_force_an_accept_in_replace_mode() {
+        // FR accepting something rustc rejected isn't something this
+        // provider can act on - it would mean fabricating a
+        // `BorrowCheckResult` rustc never computed - so it stays silent.
+        assert_eq!(
+            reconcile(FrMode::Replace, false, true),
+            Reconciliation::Silent
+        );
+        assert_eq!(
+            reconcile(FrMode::Replace, true, true),
+            Reconciliation::Silent
+        );
+        assert_eq!(
+            reconcile(FrMode::Replace, false, false),
+            Reconciliation::Silent
+        );
+    }
+
+    #[test]
+    fn check_snippet_verdicts_reports_one_verdict_per_function() {
+        let verdicts = check_snippet_verdicts("fn main() {}\nfn helper() {}\n");
+        let mut paths: Vec<&str> = verdicts.iter().map(|v| v.def_path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["helper", "main"]);
+        assert!(verdicts.iter().all(|v| v.accepted));
+    }
+
+    #[test]
+    fn check_snippet_verdicts_flags_the_function_a_borrowck_error_is_in() {
+        let verdicts = check_snippet_verdicts(
+            "fn main() {\n    let x = String::new();\n    let y = x;\n    drop(x);\n}\n",
+        );
+        let main = verdicts.iter().find(|v| v.def_path == "main").unwrap();
+        assert!(!main.accepted);
+        assert!(!main.errors.is_empty());
+    }
+
+    #[test]
+    fn source_lines_reports_the_lines_a_span_covers() {
+        let source_map =
+            syntax::source_map::SourceMap::new(syntax::source_map::FilePathMapping::empty());
+        let src = "fn main() {\n    1 + 1;\n}\n";
+        let file = source_map.new_source_file(FileName::anon_source_code(src), src.to_string());
+        let span = Span::with_root_ctxt(file.start_pos, file.end_pos);
+
+        let (lo, hi) = source_lines(&source_map, span);
+        assert_eq!(lo, 1);
+        assert_eq!(hi, 3);
     }
 }